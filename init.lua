@@ -1,27 +1,51 @@
+-- Requires Neovim 0.9 or later
+if vim.fn.has('nvim-0.9') == 0 then
+  vim.api.nvim_err_writeln('This configuration requires Neovim 0.9 or later')
+  return
+end
+
+-- cache compiled lua modules; must run before anything else is required
+if vim.loader then
+  vim.loader.enable()
+end
+
 local cmd = vim.cmd
 local g = vim.g
 local opt = vim.opt
 local hl = vim.api.nvim_set_hl
 
+-- run a plugin setup block; a missing module is expected on the first launch,
+-- before PackerSync has installed anything, so only that error stays quiet
+local function setup(fn)
+  local ok, err = pcall(fn)
+  if not ok and not tostring(err):find("module '[^']+' not found") then
+    vim.notify(tostring(err), vim.log.levels.ERROR)
+  end
+end
+
 
 --
 -- Options
 --
 opt.backup = false
 opt.completeopt = { 'menuone', 'noselect' }
+opt.exrc = true -- project-local .nvim.lua/.nvimrc; vim.secure asks before trusting each file
 opt.fileencoding = 'utf-8'
+opt.shada = { '!', "'1000", '<50', 's10', ':1000', '/1000', 'h' } -- globals, recent files, registers, history
 opt.swapfile = false
 opt.undofile = true
+opt.updatetime = 250 -- CursorHold delay
 
 -- Visual
 opt.cursorline = true
 opt.fillchars = { eob = ' ', vert = ' ' }
 opt.hlsearch = false
+opt.lazyredraw = true -- don't redraw while executing macros
 opt.number = true
 opt.relativenumber = true
 opt.ruler = false
 opt.scrolloff = 2
-opt.shortmess:append('I')
+opt.shortmess:append('IS') -- search count is shown in the statusline instead
 opt.showmode = false
 opt.sidescrolloff = 2
 opt.splitbelow = true
@@ -29,12 +53,93 @@ opt.splitright = true
 opt.termguicolors = true
 opt.wrap = false
 
+-- Spelling
+opt.spelllang = 'en_us'
+opt.spelloptions = 'camel'
+
+-- Word lists for <c-x><c-k> and <c-x><c-t>, added only when present. The
+-- thesaurus is not shipped: one group of synonyms per line, separated by
+-- spaces, e.g. "big large huge enormous"
+local dictionary = '/usr/share/dict/words'
+if vim.fn.filereadable(dictionary) == 1 then
+  opt.dictionary:append(dictionary)
+end
+local thesaurus = vim.fn.stdpath('config') .. '/thesaurus.txt'
+if vim.fn.filereadable(thesaurus) == 1 then
+  opt.thesaurus:append(thesaurus)
+end
+
 -- Formatting
 opt.expandtab = true
 opt.shiftwidth = 2
 opt.smartindent = true
 opt.timeoutlen = 500
 
+-- Search
+if vim.fn.executable('rg') == 1 then
+  opt.grepformat = '%f:%l:%c:%m'
+  opt.grepprg = 'rg --vimgrep --smart-case'
+end
+
+-- Command line
+opt.path:append('**') -- :find and gf search the project tree
+opt.wildignore:append({ '*.o', '*.obj', '*/.git/*', '*/node_modules/*' })
+opt.wildignorecase = true
+
+-- Folding
+opt.foldenable = false
+opt.foldexpr = 'nvim_treesitter#foldexpr()'
+opt.foldmethod = 'expr'
+
+
+--
+-- Filetypes
+--
+-- additions to the runtime's detection, which already knows the common
+-- extensions, filenames and shebangs
+vim.filetype.add({
+  pattern = {
+    -- `#!/usr/bin/env -S nvim -l` scripts are lua
+    ['.*'] = {
+      function(_, bufnr)
+        local line = vim.api.nvim_buf_get_lines(bufnr, 0, 1, false)[1] or ''
+        if line:match('^#!.*nvim%s+%-l') then
+          return 'lua'
+        end
+      end,
+      { priority = -math.huge },
+    },
+  },
+})
+
+-- load the runtime ftplugins now rather than after init.lua, so their FileType
+-- handler is registered first and the overrides below are applied after it
+cmd('filetype plugin indent on')
+
+-- buffer-local overrides of the global options above
+local filetype_options = {
+  c = { commentstring = '// %s' },
+  cpp = { commentstring = '// %s' },
+  gitcommit = { spell = true, textwidth = 72 },
+  go = { expandtab = false, shiftwidth = 4, tabstop = 4 },
+  lua = { keywordprg = ':help' },
+  make = { expandtab = false, shiftwidth = 8, tabstop = 8 },
+  markdown = { spell = true },
+  python = { shiftwidth = 4 },
+  text = { spell = true },
+  vim = { keywordprg = ':help' },
+}
+
+vim.api.nvim_create_autocmd('FileType', {
+  group = vim.api.nvim_create_augroup('FiletypeOptions', { clear = true }),
+  pattern = vim.tbl_keys(filetype_options),
+  callback = function(args)
+    for name, value in pairs(filetype_options[args.match]) do
+      vim.opt_local[name] = value
+    end
+  end
+})
+
 
 --
 -- Keymaps
@@ -49,9 +154,79 @@ map('n', '<c-h>', '<c-w>h')
 map('n', '<c-j>', '<c-w>j')
 map('n', '<c-k>', '<c-w>k')
 map('n', '<c-l>', '<c-w>l')
+map('n', 'gf', 'gF') -- honor file:line suffixes
+map('n', '<c-w>f', '<c-w>F')
+map('n', ']q', '<cmd>cnext<cr>')
+map('n', '[q', '<cmd>cprev<cr>')
+
+-- Open the URL or file under the cursor with the system handler
+-- (override with g:open_command, a program name or an argument list)
+if not g.open_command then
+  if vim.fn.has('win32') == 1 then
+    g.open_command = { 'cmd', '/c', 'start', '' }
+  elseif vim.fn.has('mac') == 1 then
+    g.open_command = 'open'
+  else
+    g.open_command = 'xdg-open'
+  end
+end
+map('n', 'gx', function()
+  local target = vim.fn.expand('<cfile>')
+  if target == '' then
+    return
+  end
+  local opener = g.open_command
+  if type(opener) == 'string' then
+    opener = { opener }
+  end
+  if vim.fn.executable(opener[1]) == 0 then
+    vim.notify('gx: ' .. opener[1] .. ' is not executable', vim.log.levels.ERROR)
+    return
+  end
+  vim.fn.jobstart(vim.list_extend(vim.deepcopy(opener), { target }), { detach = true })
+end)
+
+-- Substitute restricted to the visual selection (columns too, when block-wise);
+-- the pattern is typed between a \%V start anchor and a lookbehind that keeps
+-- the last matched character inside the selection
+map('x', '<leader>r', [[:s/\%V\%(\%V.\)\@<=/]] .. string.rep('<left>', 14), { desc = 'Substitute within the selection' })
+
+-- Align a motion or selection on a delimiter, e.g. <leader>aip=
+map({ 'n', 'x' }, '<leader>a', '<plug>(EasyAlign)', { remap = true })
+vim.api.nvim_create_user_command('Align', function(args)
+  cmd(args.line1 .. ',' .. args.line2 .. 'EasyAlign ' .. args.args)
+end, { nargs = '*', range = true })
+
+-- Edit a macro as text in the command-line window, e.g. "q<leader>m
+map('n', '<leader>m', function()
+  local register = vim.v.register
+  return ':<c-u>let @' .. register .. " = <c-r><c-r>=string(getreg('" .. register .. "'))<cr><c-f><left>"
+end, { expr = true })
+
+-- Replay a macro on each line of the visual selection, e.g. V3j@q, stopping at the first error
+map('x', '@', function()
+  local register = vim.fn.nr2char(vim.fn.getchar())
+  local first, last = vim.fn.line('v'), vim.fn.line('.')
+  if first > last then
+    first, last = last, first
+  end
+  vim.api.nvim_feedkeys(vim.api.nvim_replace_termcodes('<esc>', true, false, true), 'nx', false)
+
+  for lnum = first, last do
+    if lnum > vim.fn.line('$') then
+      break
+    end
+    vim.api.nvim_win_set_cursor(0, { lnum, 0 })
+    vim.v.errmsg = ''
+    local ok = pcall(cmd, 'normal @' .. register)
+    if not ok or vim.v.errmsg ~= '' then
+      break
+    end
+  end
+end)
 
 -- Search
-pcall(function()
+setup(function()
   local wk = require('which-key')
   local options = { prefix = '<leader>' }
   local mappings = {
@@ -64,6 +239,8 @@ pcall(function()
       d = { function() require('telescope.builtin').grep_string() end, 'Searches for the string under your cursor' },
       p = { function() require('telescope.builtin').live_grep() end, 'Search for a string' },
       o = { function() require('telescope.builtin').tags({ only_current_buffer = true }) end, 'Search tags in current buffer' },
+      c = { function() require('telescope.builtin').commands() end, 'Search commands' },
+      k = { function() require('telescope.builtin').keymaps() end, 'Search keymaps' },
     },
   }
   wk.register(mappings, options)
@@ -77,20 +254,38 @@ pcall(function()
 end)
 
 -- Git
-pcall(function()
+setup(function()
   local wk = require('which-key')
   local options = { prefix = '<leader>' }
   local mappings = {
     g = {
       name = 'Git',
       b = { function() require('gitsigns').blame_line() end, 'Run git blame on the current line' },
+      p = { function() require('gitsigns').preview_hunk() end, 'Preview the hunk under the cursor' },
+      s = { '<cmd>Git<cr>', 'Open the git status buffer' },
+    }
+  }
+  wk.register(mappings, options)
+end)
+
+-- Plugins
+setup(function()
+  local wk = require('which-key')
+  local options = { prefix = '<leader>' }
+  local mappings = {
+    p = {
+      name = 'Plugins',
+      i = { '<cmd>PackerInstall<cr>', 'Install missing plugins' },
+      u = { '<cmd>PackerUpdate<cr>', 'Update installed plugins' },
+      s = { '<cmd>PackerSync<cr>', 'Install, update and clean plugins' },
+      S = { '<cmd>PackerStatus<cr>', 'Show plugin status' },
     }
   }
   wk.register(mappings, options)
 end)
 
 -- Explore
-pcall(function()
+setup(function()
   local wk = require('which-key')
   local options = { prefix = '<leader>' }
   local mappings = {
@@ -109,11 +304,51 @@ map('n', '[d', vim.diagnostic.goto_prev)
 map('n', ']d', vim.diagnostic.goto_next)
 map('n', '<leader>q', vim.diagnostic.setloclist)
 
+-- Toggle
+setup(function()
+  local wk = require('which-key')
+  local options = { prefix = '<leader>' }
+  local virtual_text
+  local mappings = {
+    t = {
+      name = 'Toggle',
+      v = {
+        function()
+          local current = vim.diagnostic.config().virtual_text
+          virtual_text = current or virtual_text
+          vim.diagnostic.config({ virtual_text = not current and virtual_text })
+        end,
+        'Toggle diagnostic virtual text'
+      },
+      h = { function() require('lsp-inlayhints').toggle() end, 'Toggle inlay hints' },
+      s = { '<cmd>setlocal spell!<cr>', 'Toggle spell checking' },
+    }
+  }
+  wk.register(mappings, options)
+end)
+
+-- LSP
+setup(function()
+  local wk = require('which-key')
+  local options = { prefix = '<leader>' }
+  local mappings = {
+    l = {
+      name = 'LSP',
+      i = { '<cmd>LspInfo<cr>', 'Show attached language servers' },
+      r = { '<cmd>LspRestart<cr>', 'Restart language servers' },
+    },
+    c = { name = 'Code' },
+    r = { name = 'Refactor' },
+    w = { name = 'Workspace' },
+  }
+  wk.register(mappings, options)
+end)
+
 -- LSP/Buffer
 local set_buffer_maps = function(bufnr)
   local opts = { buffer = bufnr }
   map('n', 'gD', vim.lsp.buf.declaration, opts)
-  map('n', 'gd', vim.lsp.buf.definition, opts)
+  map('n', 'gd', require('telescope.builtin').lsp_definitions, opts)
   map('n', 'K', vim.lsp.buf.hover, opts)
   map('n', 'gi', vim.lsp.buf.implementation, opts)
   -- map('n', '<C-k>', vim.lsp.buf.signature_help, opts)
@@ -122,12 +357,122 @@ local set_buffer_maps = function(bufnr)
   map('n', '<leader>wl', function() vim.inspect(vim.lsp.buf.list_workspace_folders()) end, opts)
   map('n', '<leader>D', vim.lsp.buf.type_definition, opts)
   map('n', '<leader>rn', vim.lsp.buf.rename, opts)
-  map('n', 'gr', vim.lsp.buf.references, opts)
-  map('n', '<leader>ca', vim.lsp.buf.code_action, opts)
+  map('n', 'grn', vim.lsp.buf.rename, opts)
+  map('n', 'grr', vim.lsp.buf.references, opts)
+  map({ 'n', 'x' }, '<leader>ca', vim.lsp.buf.code_action, opts)
   map('n', '<leader>so', require('telescope.builtin').lsp_document_symbols, opts)
 end
 
 
+--
+-- Batch Execution
+--
+local function run_in_buffers(bufnrs, command)
+  local errors = {}
+  for _, bufnr in ipairs(bufnrs) do
+    vim.fn.bufload(bufnr)
+    vim.api.nvim_buf_call(bufnr, function()
+      -- visible buffers run in their own window, so keep its cursor where it was
+      local view = vim.fn.winsaveview()
+      local ok, err = pcall(cmd, command)
+      vim.fn.winrestview(view)
+      if not ok then
+        local name = vim.fn.bufname(bufnr)
+        table.insert(errors, (name ~= '' and name or 'buffer ' .. bufnr) .. ': ' .. err)
+      end
+    end)
+  end
+  if #errors > 0 then
+    vim.notify(table.concat(errors, '\n'), vim.log.levels.ERROR)
+  end
+end
+
+vim.api.nvim_create_user_command('Bufdo', function(args)
+  local bufnrs = vim.tbl_map(function(info) return info.bufnr end, vim.fn.getbufinfo({ buflisted = 1 }))
+  run_in_buffers(bufnrs, args.args)
+end, { nargs = '+', complete = 'command' })
+
+vim.api.nvim_create_user_command('Argdo', function(args)
+  run_in_buffers(vim.tbl_map(vim.fn.bufadd, vim.fn.argv()), args.args)
+end, { nargs = '+', complete = 'command' })
+
+
+--
+-- Scratch Buffers
+--
+vim.api.nvim_create_user_command('Scratch', function(args)
+  cmd('enew')
+  vim.bo.buftype = 'nofile'
+  vim.bo.bufhidden = 'hide'
+  vim.bo.swapfile = false
+  if args.args ~= '' then
+    vim.api.nvim_buf_set_name(0, args.args)
+  end
+end, { nargs = '?' })
+
+
+--
+-- Large Files
+--
+local large_file_size = 10 * 1024 * 1024
+local large_files = vim.api.nvim_create_augroup('LargeFiles', { clear = true })
+
+vim.api.nvim_create_autocmd('BufReadPre', {
+  group = large_files,
+  callback = function(args)
+    local large = vim.fn.getfsize(args.match) > large_file_size
+    vim.b[args.buf].large_file = large
+    if large then
+      -- tree-sitter folding parses the whole file before it can be shown
+      vim.opt_local.foldmethod = 'manual'
+      -- every undo state of a huge buffer is held in memory and written to the undo file
+      vim.opt_local.undofile = false
+      vim.opt_local.undolevels = 100
+      -- indent-blankline's current context lookup walks the syntax tree
+      vim.b[args.buf].indent_blankline_enabled = false
+    end
+  end
+})
+
+-- incremental search re-scans on every keystroke, which blocks input in huge buffers;
+-- the global value is only borrowed while such a buffer is current
+local saved_incsearch
+
+vim.api.nvim_create_autocmd('BufEnter', {
+  group = large_files,
+  callback = function(args)
+    if vim.b[args.buf].large_file and saved_incsearch == nil then
+      saved_incsearch = opt.incsearch:get()
+      opt.incsearch = false
+    end
+  end
+})
+
+vim.api.nvim_create_autocmd('BufLeave', {
+  group = large_files,
+  callback = function(args)
+    if vim.b[args.buf].large_file and saved_incsearch ~= nil then
+      opt.incsearch = saved_incsearch
+      saved_incsearch = nil
+    end
+  end
+})
+
+
+--
+-- Local Overrides
+--
+-- sourced before the plugins are set up, so settings they read at startup
+-- (g:files_key, g:format_on_save) can be changed here
+local local_config = vim.fn.stdpath('config') .. '/local.vim'
+if vim.fn.filereadable(local_config) == 1 then
+  -- report where the error happened, not just what it was; the first failing
+  -- line stops the rest of local.vim from being sourced
+  cmd('try | source ' .. vim.fn.fnameescape(local_config) ..
+    " | catch | echohl ErrorMsg | echomsg v:throwpoint . ': ' . v:exception | echohl None | endtry")
+end
+
+
 --
 -- Plugins
 --
@@ -145,19 +490,33 @@ require('packer').startup({
     use({ 'nvim-telescope/telescope-fzf-native.nvim', requires = 'nvim-telescope/telescope.nvim', run = 'make' })
     use({ 'nvim-telescope/telescope-file-browser.nvim', requires = 'nvim-telescope/telescope.nvim' })
     use({ 'nvim-telescope/telescope-packer.nvim', requires = 'nvim-telescope/telescope.nvim' })
-    use({ 'nvim-treesitter/nvim-treesitter' })
+    use({ 'nvim-telescope/telescope-ui-select.nvim', requires = 'nvim-telescope/telescope.nvim' })
+    use({ 'nvim-treesitter/nvim-treesitter', run = ':TSUpdate' })
+    use({ 'nvim-treesitter/nvim-treesitter-textobjects', requires = 'nvim-treesitter/nvim-treesitter' })
+    use({ 'HiPhish/rainbow-delimiters.nvim', requires = 'nvim-treesitter/nvim-treesitter' })
+    use({ 'lukas-reineke/indent-blankline.nvim', tag = 'v2.20.8' })
+    use({ 'folke/neodev.nvim' })
     use({ 'williamboman/mason.nvim' })
     use({ 'williamboman/mason-lspconfig.nvim' })
     use({ 'neovim/nvim-lspconfig' })
+    use({ 'simrat39/symbols-outline.nvim' })
+    use({ 'lvimuser/lsp-inlayhints.nvim' })
     use({ 'hrsh7th/nvim-cmp' })
     use({ 'hrsh7th/cmp-nvim-lsp', requires = 'hrsh7th/nvim-cmp' })
+    use({ 'hrsh7th/cmp-nvim-lsp-signature-help', requires = 'hrsh7th/nvim-cmp' })
     use({ 'hrsh7th/cmp-nvim-lua', requires = 'hrsh7th/nvim-cmp' })
     use({ 'hrsh7th/cmp-buffer', requires = 'hrsh7th/nvim-cmp' })
     use({ 'hrsh7th/cmp-path', requires = 'hrsh7th/nvim-cmp' })
+    use({ 'hrsh7th/cmp-cmdline', requires = 'hrsh7th/nvim-cmp' })
     use({ 'saadparwaiz1/cmp_luasnip', requires = 'hrsh7th/nvim-cmp' })
     use({ 'L3MON4D3/LuaSnip', requires = 'hrsh7th/nvim-cmp' })
+    use({ 'rafamadriz/friendly-snippets', requires = 'L3MON4D3/LuaSnip' })
     use({ 'nvim-neo-tree/neo-tree.nvim', branch = 'v2.x', requires = { 'nvim-lua/plenary.nvim', 'kyazdani42/nvim-web-devicons', 'MunifTanjim/nui.nvim' } })
+    use({ 'kylechui/nvim-surround' })
+    use({ 'mg979/vim-visual-multi' })
+    use({ 'junegunn/vim-easy-align' })
     use({ 'lewis6991/gitsigns.nvim' })
+    use({ 'tpope/vim-fugitive' })
     use({ 'feline-nvim/feline.nvim', requires = 'kyazdani42/nvim-web-devicons' })
     use({ 'folke/which-key.nvim' })
   end
@@ -167,7 +526,7 @@ require('packer').startup({
 --
 -- Telescope
 --
-pcall(function()
+setup(function()
   local telescope = require('telescope')
   local options = {
     defaults = {
@@ -181,24 +540,117 @@ pcall(function()
   telescope.load_extension('fzf')
   telescope.load_extension('file_browser')
   telescope.load_extension('packer')
+  telescope.load_extension('ui-select')
+
+  local builtin = require('telescope.builtin')
+  local files_key = g.files_key or '<c-p>'
+  local find_files = function() builtin.find_files({ previewer = false }) end
+
+  map('n', files_key, find_files)
+  map('n', 'z=', function()
+    if vim.v.count > 0 then
+      cmd('normal! ' .. vim.v.count .. 'z=')
+    else
+      builtin.spell_suggest()
+    end
+  end)
+  vim.api.nvim_create_user_command('Files', find_files, {})
+  vim.api.nvim_create_user_command('Buffers', function() builtin.buffers() end, {})
+  vim.api.nvim_create_user_command('Commands', function() builtin.commands() end, {})
+  vim.api.nvim_create_user_command('Recent', function() builtin.oldfiles() end, {})
+  vim.api.nvim_create_user_command('Keymaps', function() builtin.keymaps() end, {})
 end)
 
 
 --
 -- Treesitter
 --
-pcall(function()
+setup(function()
+  local is_large_file = function(_, bufnr) return vim.b[bufnr].large_file end
+
   require('nvim-treesitter.configs').setup({
-    highlight = { enable = true },
+    highlight = { enable = true, disable = is_large_file },
+    indent = { enable = true, disable = is_large_file },
+    incremental_selection = {
+      enable = true,
+      -- the plugin defaults; grn/grc/grm are visual-mode only, so normal-mode grn stays rename
+      keymaps = {
+        init_selection = 'gnn',
+        node_incremental = 'grn',
+        scope_incremental = 'grc',
+        node_decremental = 'grm'
+      }
+    },
+    textobjects = {
+      select = {
+        enable = true,
+        lookahead = true,
+        keymaps = {
+          ['aa'] = '@parameter.outer',
+          ['ia'] = '@parameter.inner'
+        }
+      },
+      move = {
+        enable = true,
+        set_jumps = true,
+        goto_next_start = { [']m'] = '@function.outer', [']]'] = '@function.outer' },
+        goto_next_end = { [']M'] = '@function.outer', [']['] = '@function.outer' },
+        goto_previous_start = { ['[m'] = '@function.outer', ['[['] = '@function.outer' },
+        goto_previous_end = { ['[M'] = '@function.outer', ['[]'] = '@function.outer' }
+      }
+    },
     ensure_installed = { 'bash', 'c', 'cpp', 'go', 'json', 'lua', 'markdown', 'yaml' }
   })
 end)
 
 
+--
+-- Rainbow Delimiters
+--
+setup(function()
+  local rainbow = require('rainbow-delimiters')
+
+  g.rainbow_delimiters = {
+    strategy = {
+      -- no strategy means no highlighting; large files are skipped like treesitter's own modules
+      [''] = function(bufnr)
+        if vim.b[bufnr].large_file then
+          return nil
+        end
+        return rainbow.strategy['global']
+      end
+    },
+    blacklist = { 'markdown' },
+    highlight = {
+      'RainbowDelimiterBlue',
+      'RainbowDelimiterViolet',
+      'RainbowDelimiterCyan',
+      'RainbowDelimiterYellow',
+      'RainbowDelimiterGreen',
+      'RainbowDelimiterOrange',
+      'RainbowDelimiterRed'
+    }
+  }
+end)
+
+
+--
+-- Indent Guides
+--
+setup(function()
+  require('indent_blankline').setup({
+    char = '│',
+    show_current_context = true,
+    show_trailing_blankline_indent = false,
+    filetype_exclude = { 'help', 'neo-tree', 'packer' }
+  })
+end)
+
+
 --
 -- Completion
 --
-pcall(function()
+setup(function()
   local cmp = require('cmp')
   local window = require('cmp.utils.window')
 
@@ -237,6 +689,16 @@ pcall(function()
     TypeParameter = '',
   }
 
+  local source_names = {
+    buffer = '[Buffer]',
+    cmdline = '[Cmd]',
+    luasnip = '[Snippet]',
+    nvim_lsp = '[LSP]',
+    nvim_lsp_signature_help = '[Signature]',
+    nvim_lua = '[Lua]',
+    path = '[Path]',
+  }
+
   local function border(hl_name)
     return {
       { '┌', hl_name },
@@ -265,8 +727,9 @@ pcall(function()
       end
     },
     formatting = {
-      format = function(_, vim_item)
+      format = function(entry, vim_item)
         vim_item.kind = string.format('%s %s', icons[vim_item.kind], vim_item.kind)
+        vim_item.menu = source_names[entry.source.name]
         return vim_item
       end
     },
@@ -274,7 +737,7 @@ pcall(function()
       ['<c-p>'] = cmp.mapping.select_prev_item(),
       ['<c-n>'] = cmp.mapping.select_next_item(),
       ['<c-d>'] = cmp.mapping.scroll_docs(-4),
-      ['<c-f>'] = cmp.mapping.scroll_docs(-4),
+      ['<c-f>'] = cmp.mapping.scroll_docs(4),
       ['<c-space>'] = cmp.mapping.complete(),
       ['<c-e>'] = cmp.mapping.close(),
       ['<cr>'] = cmp.mapping.confirm({ behavior = cmp.ConfirmBehavior.Insert, select = true }),
@@ -299,21 +762,61 @@ pcall(function()
     },
     sources = {
       { name = 'nvim_lsp' },
+      { name = 'nvim_lsp_signature_help' },
       { name = 'luasnip' },
-      { name = 'buffer' },
+      {
+        name = 'buffer',
+        option = {
+          -- words from every buffer visible in this tab, not just the current one;
+          -- floating windows, special buffers and large files are left out
+          get_bufnrs = function()
+            local bufnrs = {}
+            for _, win in ipairs(vim.api.nvim_tabpage_list_wins(0)) do
+              local bufnr = vim.api.nvim_win_get_buf(win)
+              if vim.api.nvim_win_get_config(win).relative == ''
+                and vim.bo[bufnr].buftype == ''
+                and not vim.b[bufnr].large_file then
+                bufnrs[bufnr] = true
+              end
+            end
+            return vim.tbl_keys(bufnrs)
+          end
+        }
+      },
       { name = 'nvim_lua' },
       { name = 'path' }
     }
   }
 
   cmp.setup(options)
+
+  cmp.setup.cmdline(':', {
+    mapping = cmp.mapping.preset.cmdline(),
+    sources = { { name = 'cmdline' } } -- cmdline completion already honours wildignore
+  })
+
+  cmp.setup.cmdline({ '/', '?' }, {
+    mapping = cmp.mapping.preset.cmdline(),
+    sources = {
+      {
+        name = 'buffer',
+        option = {
+          -- indexing a huge buffer stalls input as soon as / is typed
+          get_bufnrs = function()
+            local bufnr = vim.api.nvim_get_current_buf()
+            return vim.b[bufnr].large_file and {} or { bufnr }
+          end
+        }
+      }
+    }
+  })
 end)
 
 
 --
 -- Snippets
 --
-pcall(function()
+setup(function()
   local luasnip = require('luasnip')
 
   luasnip.config.set_config({
@@ -322,21 +825,40 @@ pcall(function()
   })
 
   require('luasnip.loaders.from_vscode').lazy_load()
+  -- user snippets, one vscode-style <filetype>.json per language plus a package.json
+  require('luasnip.loaders.from_vscode').lazy_load({ paths = { vim.fn.stdpath('config') .. '/snippets' } })
+end)
+
+
+--
+-- Surround
+--
+setup(function()
+  require('nvim-surround').setup()
 end)
 
 
 --
 -- LSP
 --
-pcall(function()
+-- editor API types and docs for lua config and plugin code; must precede lspconfig
+setup(function()
+  require('neodev').setup()
+end)
+
+setup(function()
   require('mason').setup()
 end)
 
-pcall(function()
+setup(function()
+  require('lsp-inlayhints').setup()
+end)
+
+setup(function()
   require('mason-lspconfig').setup({ automatic_installation = true })
 end)
 
-pcall(function()
+setup(function()
   local lspconfig = require('lspconfig')
 
   local function lspSymbol(name, icon)
@@ -356,9 +878,10 @@ pcall(function()
     signs = true,
     underline = true,
     update_in_insert = false,
+    severity_sort = true,
   }
 
-  vim.lsp.handlers['textDocument/hover'] = vim.lsp.with(vim.lsp.handlers.hover, { border = 'single' })
+  vim.lsp.handlers['textDocument/hover'] = vim.lsp.with(vim.lsp.handlers.hover, { border = 'single', max_width = 80, max_height = 20 })
   vim.lsp.handlers['textDocument/signatureHelp'] = vim.lsp.with(vim.lsp.handlers.signature_help, { border = 'single' })
 
   -- suppress error messages from lang servers
@@ -383,48 +906,109 @@ pcall(function()
     return opts
   end
 
-  local on_attach = function(_, bufnr)
+  -- opt-in, e.g. `:let g:format_on_save = v:true`
+  if g.format_on_save == nil then
+    g.format_on_save = false
+  end
+  local format_group = vim.api.nvim_create_augroup('LspFormat', { clear = true })
+
+  local on_attach = function(client, bufnr)
     set_buffer_maps(bufnr)
-    vim.api.nvim_create_user_command('Format', vim.lsp.buf.formatting, {})
+    pcall(function() require('lsp-inlayhints').on_attach(client, bufnr) end)
+    vim.api.nvim_buf_create_user_command(bufnr, 'Format', function(args)
+      local range
+      if args.range > 0 then
+        local last = vim.api.nvim_buf_get_lines(0, args.line2 - 1, args.line2, true)[1]
+        range = { start = { args.line1, 0 }, ['end'] = { args.line2, #last } }
+      end
+      vim.lsp.buf.format({ range = range })
+    end, { range = true })
+    vim.api.nvim_buf_create_user_command(bufnr, 'Rename', function(args)
+      vim.lsp.buf.rename(args.args ~= '' and args.args or nil)
+    end, { nargs = '?' })
+
+    vim.api.nvim_clear_autocmds({ group = format_group, buffer = bufnr })
+    vim.api.nvim_create_autocmd('BufWritePre', {
+      group = format_group,
+      buffer = bufnr,
+      callback = function()
+        if g.format_on_save then
+          vim.lsp.buf.format({ bufnr = bufnr })
+        end
+      end
+    })
   end
 
-  local capabilities = vim.lsp.protocol.make_client_capabilities()
-  capabilities = require('cmp_nvim_lsp').update_capabilities(capabilities)
-
-  lspconfig.clangd.setup({ on_attach = on_attach, capabilities = capabilities })
-  lspconfig.gopls.setup({ on_attach = on_attach, capabilities = capabilities })
-  lspconfig.sumneko_lua.setup({
-    on_attach = on_attach,
-    capabilities = capabilities,
-    settings = {
-      Lua = {
-        diagnostics = {
-          globals = { 'vim' }
-        },
-        workspace = {
-          library = {
-            [vim.fn.expand('$VIMRUNTIME/lua')] = true,
-            [vim.fn.expand('$VIMRUNTIME/lua/vim/lsp')] = true
+  local capabilities = require('cmp_nvim_lsp').default_capabilities()
+
+  -- servers are started by lspconfig for their filetypes and installed by mason;
+  -- lspconfig does not restart a server that crashes, :LspRestart (<leader>lr) does
+  local servers = {
+    clangd = {},
+    gopls = {
+      settings = {
+        gopls = {
+          hints = {
+            assignVariableTypes = true,
+            compositeLiteralFields = true,
+            parameterNames = true,
+            rangeVariableTypes = true
+          }
+        }
+      }
+    },
+    lua_ls = {
+      settings = {
+        Lua = {
+          hint = {
+            enable = true
           }
         }
       }
     }
+  }
+
+  for name, config in pairs(servers) do
+    config.on_attach = on_attach
+    config.capabilities = capabilities
+    config.flags = { allow_incremental_sync = true, debounce_text_changes = 150 }
+    lspconfig[name].setup(config)
+  end
+end)
+
+
+--
+-- Outline
+--
+setup(function()
+  require('symbols-outline').setup({
+    highlight_hovered_item = true,
+    auto_preview = false
   })
+  vim.api.nvim_create_user_command('Outline', 'SymbolsOutline', {})
 end)
 
 
 --
 -- Git
 --
-pcall(function()
-  require('gitsigns').setup()
+setup(function()
+  require('gitsigns').setup({
+    on_attach = function(bufnr)
+      local gitsigns = package.loaded.gitsigns
+      local opts = { buffer = bufnr }
+      map('n', ']h', gitsigns.next_hunk, opts)
+      map('n', '[h', gitsigns.prev_hunk, opts)
+      vim.api.nvim_buf_create_user_command(bufnr, 'HunkPreview', gitsigns.preview_hunk, {})
+    end
+  })
 end)
 
 
 --
 -- Statusline
 --
-pcall(function()
+setup(function()
   local feline = require('feline')
 
   local options = {
@@ -479,6 +1063,26 @@ pcall(function()
     }
   }
 
+  options.read_only = {
+    provider = function()
+      if vim.bo.buftype == '' and (vim.bo.readonly or not vim.bo.modifiable) then
+        return ' [RO] '
+      end
+      return ''
+    end,
+    hl = 'FelineReadOnly'
+  }
+
+  options.arg_list = {
+    provider = function()
+      if vim.fn.argc() < 2 then
+        return ''
+      end
+      return string.format(' (%d of %d) ', vim.fn.argidx() + 1, vim.fn.argc())
+    end,
+    hl = 'FelineArgList'
+  }
+
   options.diff = {
     add = {
       provider = 'git_diff_added',
@@ -576,6 +1180,86 @@ pcall(function()
     hl = 'FelineLspProgress'
   }
 
+  -- the count is shown only right after a search or n/N, not whenever the cursor
+  -- rests on a match of an old pattern (which shada keeps across sessions)
+  local show_search_count = false
+  local search_jumped = false
+  local function searched()
+    show_search_count = true
+    search_jumped = true
+  end
+
+  for _, key in ipairs({ 'n', 'N' }) do
+    map({ 'n', 'x' }, key, function()
+      searched()
+      return key
+    end, { expr = true })
+  end
+
+  local search_group = vim.api.nvim_create_augroup('FelineSearchCount', { clear = true })
+  vim.api.nvim_create_autocmd('CmdlineLeave', {
+    group = search_group,
+    callback = function(args)
+      if (args.match == '/' or args.match == '?') and not vim.v.event.abort then
+        searched()
+      end
+    end
+  })
+  -- the jump to the match moves the cursor once; any later move leaves the match
+  vim.api.nvim_create_autocmd('CursorMoved', {
+    group = search_group,
+    callback = function()
+      if search_jumped then
+        search_jumped = false
+      else
+        show_search_count = false
+      end
+    end
+  })
+  vim.api.nvim_create_autocmd('InsertEnter', {
+    group = search_group,
+    callback = function()
+      show_search_count = false
+    end
+  })
+
+  options.search_count = {
+    provider = function()
+      -- this runs on every redraw, so skip the search entirely in huge buffers
+      if not show_search_count or vim.b.large_file then
+        return ''
+      end
+      local ok, count = pcall(vim.fn.searchcount, { maxcount = 999, timeout = 50 })
+      if not ok or (count.total or 0) == 0 or count.exact_match == 0 then
+        return ''
+      end
+      if count.incomplete == 1 then
+        return ' [?/??] '
+      end
+      local total = count.incomplete == 2 and '>' .. count.maxcount or count.total
+      return string.format(' [%d/%s] ', count.current, total)
+    end,
+    hl = 'FelineSearchCount'
+  }
+
+  options.macro_recording = {
+    provider = function()
+      local register = vim.fn.reg_recording()
+      if register == '' then
+        return ''
+      end
+      return ' recording @' .. register .. ' '
+    end,
+    hl = 'FelineMacroRecording'
+  }
+
+  vim.api.nvim_create_autocmd({ 'RecordingEnter', 'RecordingLeave' }, {
+    group = vim.api.nvim_create_augroup('FelineRecording', { clear = true }),
+    callback = function()
+      vim.schedule(function() cmd('redrawstatus') end)
+    end
+  })
+
   options.mode_hlgroups = {
     ['n'] = { 'NORMAL', 'FelineNormalMode' },
     ['no'] = { 'N-PENDING', 'FelineNormalMode' },
@@ -676,6 +1360,13 @@ pcall(function()
     hl = 'FelinePositionIcon'
   }
 
+  options.cursor_position = {
+    provider = function()
+      return ' %l:%v'
+    end,
+    hl = 'FelineCurrentLine'
+  }
+
   options.current_line = {
     provider = function()
       local current_line = vim.fn.line '.'
@@ -708,6 +1399,8 @@ pcall(function()
   add_table(options.left, options.main_icon)
   add_table(options.left, options.file_name)
   add_table(options.left, options.dir_name)
+  add_table(options.left, options.read_only)
+  add_table(options.left, options.arg_list)
 
   add_table(options.left, options.lsp_icon)
   add_table(options.left, options.diagnostic.error)
@@ -717,6 +1410,8 @@ pcall(function()
 
   -- middle
   add_table(options.middle, options.lsp_progress)
+  add_table(options.middle, options.search_count)
+  add_table(options.middle, options.macro_recording)
 
   -- right
   add_table(options.right, options.separator_git)
@@ -732,6 +1427,7 @@ pcall(function()
   add_table(options.right, options.mode_empty_space)
   add_table(options.right, options.separator_position)
   add_table(options.right, options.position_icon)
+  add_table(options.right, options.cursor_position)
   add_table(options.right, options.current_line)
 
   -- Initialize the components table
@@ -757,12 +1453,26 @@ end)
 -- Neotree
 --
 pcall(function() vim.cmd('let g:neo_tree_remove_legacy_commands = 1') end)
+setup(function()
+  require('neo-tree').setup({
+    filesystem = {
+      -- `:e some_dir/` opens a listing in the current window
+      hijack_netrw_behavior = 'open_current',
+      window = {
+        mappings = {
+          ['-'] = 'navigate_up'
+        }
+      }
+    }
+  })
+end)
+vim.api.nvim_create_user_command('Tree', 'Neotree toggle', {})
 
 
 --
 -- Keybinding Help
 --
-pcall(function()
+setup(function()
   local options = {
     key_labels = {
       ['<space>'] = 'SP'
@@ -816,10 +1526,26 @@ hl(0, 'TelescopePreviewTitle',  { fg = base_16.base0B, bg = base_16.base00 })
 hl(0, 'TelescopePromptTitle',   { fg = base_16.base0A, bg = base_16.base00 })
 hl(0, 'TelescopeResultsTitle',  { fg = base_16.base0C, bg = base_16.base00 })
 
+-- LSP
+hl(0, 'LspInlayHint', { fg = base_16.base03, bg = base_16.base00 })
+
 -- CMP
 hl(0, 'CmpBorder',    { fg = base_16.base0A, bg = base_16.base00 })
 hl(0, 'CmpDocBorder', { fg = base_16.base0A, bg = base_16.base00 })
 
+-- Rainbow
+hl(0, 'RainbowDelimiterBlue',   { fg = base_16.base0D })
+hl(0, 'RainbowDelimiterViolet', { fg = base_16.base0E })
+hl(0, 'RainbowDelimiterCyan',   { fg = base_16.base0C })
+hl(0, 'RainbowDelimiterYellow', { fg = base_16.base0A })
+hl(0, 'RainbowDelimiterGreen',  { fg = base_16.base0B })
+hl(0, 'RainbowDelimiterOrange', { fg = base_16.base09 })
+hl(0, 'RainbowDelimiterRed',    { fg = base_16.base08 })
+
+-- Indent Guides
+hl(0, 'IndentBlanklineChar',        { fg = base_16.base02 })
+hl(0, 'IndentBlanklineContextChar', { fg = base_16.base03 })
+
 -- Feline
 hl(0, 'Feline',                  { fg = base_16.base05, bg = base_16.base01 })
 hl(0, 'FelineIcon',              { fg = base_16.base01, bg = base_16.base0D })
@@ -828,6 +1554,8 @@ hl(0, 'FelineFileName',          { fg = base_16.base06, bg = base_16.base03 })
 hl(0, 'FelineFileNameSeparator', { fg = base_16.base03, bg = base_16.base02 })
 hl(0, 'FelineDirName',           { fg = base_16.base05, bg = base_16.base02 })
 hl(0, 'FelineDirNameSeparator',  { fg = base_16.base02, bg = base_16.base01 })
+hl(0, 'FelineReadOnly',          { fg = base_16.base08, bg = base_16.base01 })
+hl(0, 'FelineArgList',           { fg = base_16.base04, bg = base_16.base01 })
 hl(0, 'FelineGit',               { fg = base_16.base05, bg = base_16.base02 })
 hl(0, 'FelineGitAddIcon',        { fg = base_16.base0B, bg = base_16.base02 })
 hl(0, 'FelineGitBranchIcon',     { fg = base_16.base05, bg = base_16.base02 })
@@ -839,6 +1567,8 @@ hl(0, 'FelineLspHints',          { fg = base_16.base0E, bg = base_16.base01 })
 hl(0, 'FelineLspInfo',           { fg = base_16.base0B, bg = base_16.base01 })
 hl(0, 'FelineLspIcon',           { fg = base_16.base0B, bg = base_16.base01 })
 hl(0, 'FelineLspProgress',       { fg = base_16.base0D, bg = base_16.base01 })
+hl(0, 'FelineSearchCount',       { fg = base_16.base0A, bg = base_16.base01 })
+hl(0, 'FelineMacroRecording',    { fg = base_16.base08, bg = base_16.base01 })
 hl(0, 'FelineNormalMode',        { fg = base_16.base08, bg = base_16.base00 })
 hl(0, 'FelineInsertMode',        { fg = base_16.base0E, bg = base_16.base00 })
 hl(0, 'FelineTerminalMode',      { fg = base_16.base0B, bg = base_16.base00 })
@@ -1,10 +1,19 @@
 #!/bin/zsh
 
+# Requires Neovim 0.9 or later
+if ! nvim --clean --headless -c 'if has("nvim-0.9") | qall | else | cquit | endif'; then
+  echo 'This configuration requires Neovim 0.9 or later' >&2
+  exit 1
+fi
+
+data_dir="${XDG_DATA_HOME:-$HOME/.local/share}/nvim"
+cache_dir="${XDG_CACHE_HOME:-$HOME/.cache}/nvim"
+
 # Cleanup old neovim files
-rm -rf ~/.local/share/nvim ~/.cache/nvim
+rm -rf "$data_dir" "$cache_dir"
 
 # Install packer
-git clone --depth 1 https://github.com/wbthomason/packer.nvim ~/.local/share/nvim/site/pack/packer/start/packer.nvim
+git clone --depth 1 https://github.com/wbthomason/packer.nvim "$data_dir/site/pack/packer/start/packer.nvim"
 
 # Install plugins
 nvim --headless -c 'autocmd User PackerComplete quitall' -c 'PackerSync'